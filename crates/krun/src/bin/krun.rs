@@ -8,7 +8,7 @@ use anyhow::{anyhow, Context, Result};
 use krun::cli_options::options;
 use krun::cpu::{get_fallback_cores, get_performance_cores};
 use krun::env::{find_krun_exec, prepare_env_vars};
-use krun::launch::{launch_or_lock, LaunchResult};
+use krun::launch::{launch_or_lock, spawn_listeners, LaunchResult};
 use krun::net::{connect_to_passt, start_passt};
 use krun::types::MiB;
 use krun_sys::{
@@ -65,7 +65,7 @@ fn main() -> Result<()> {
         return Err(anyhow!("real user ID or effective user ID is 0"));
     }
 
-    let options = options().fallback_to_usage().run();
+    let options = options().run();
 
     let (_lock_file, command, command_args, env) = match launch_or_lock(
         options.server_port,
@@ -73,10 +73,10 @@ fn main() -> Result<()> {
         options.command_args,
         options.env,
     )? {
-        LaunchResult::LaunchRequested => {
-            // There was a krun instance already running and we've requested it
-            // to launch the command successfully, so all the work is done.
-            return Ok(());
+        LaunchResult::LaunchRequested { exit_code } => {
+            // There was a krun instance already running and it ran the command
+            // on our behalf; exit with whatever status it reported back.
+            std::process::exit(exit_code);
         },
         LaunchResult::LockAcquired {
             lock_file,
@@ -86,6 +86,9 @@ fn main() -> Result<()> {
         } => (lock_file, command, command_args, env),
     };
 
+    spawn_listeners(options.server_port, options.listen, options.token.clone())
+        .context("Failed to start the launch protocol listener")?;
+
     {
         // Set the log level to "off".
         //
@@ -190,7 +193,7 @@ fn main() -> Result<()> {
         }
     }
 
-    {
+    if options.gpu_virgl {
         let virgl_flags = VIRGLRENDERER_USE_EGL
             | VIRGLRENDERER_DRM
             | VIRGLRENDERER_THREAD_SYNC
@@ -276,13 +279,55 @@ fn main() -> Result<()> {
         }
     }
 
+    // Forward the native Wayland compositor socket into the guest as a socket
+    let mut wayland_display = None;
+    if let Ok(run_path) = env::var("XDG_RUNTIME_DIR") {
+        let display = env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_owned());
+        let socket_path = Path::new(&run_path).join(&display);
+        if socket_path.exists() {
+            let socket_path_cstr = CString::new(
+                socket_path
+                    .to_str()
+                    .expect("socket_path should not contain invalid UTF-8"),
+            )
+            .context("Failed to process dynamic socket path as it contains NUL character")?;
+            // SAFETY: `socket_path_cstr` is a pointer to a `CString` with long enough lifetime.
+            let err = unsafe { krun_add_vsock_port(ctx_id, 6001, socket_path_cstr.as_ptr()) };
+            if err < 0 {
+                let err = Errno::from_raw_os_error(-err);
+                return Err(err).context("Failed to configure vsock for host Wayland socket");
+            }
+            wayland_display = Some(display);
+        }
+    }
+
+    // Forward user-supplied host UNIX sockets (dbus, a game launcher's IPC, ...)
+    // into the guest on the requested vsock ports.
+    for (port, host_path) in options.vsock_forwards {
+        let host_path = CString::new(
+            host_path
+                .to_str()
+                .context("Failed to process `--vsock` path as it contains invalid UTF-8")?,
+        )
+        .context("Failed to process `--vsock` path as it contains a NUL character")?;
+        // SAFETY: `host_path` is a pointer to a `CString` with long enough lifetime.
+        let err = unsafe { krun_add_vsock_port(ctx_id, port, host_path.as_ptr()) };
+        if err < 0 {
+            let err = Errno::from_raw_os_error(-err);
+            return Err(err).context("Failed to configure vsock for `--vsock` forward");
+        }
+    }
+
     let username = env::var("USER").context("Failed to get username from environment")?;
     let user = User::from_name(&username)
         .map_err(Into::into)
         .and_then(|user| user.ok_or_else(|| anyhow!("requested entry not found")))
         .with_context(|| format!("Failed to get user `{username}` from user database"))?;
+    // A `--config`/`workdir` override takes precedence over the user's home
+    // directory, which otherwise remains the default just for completeness.
+    let workdir = options.workdir.unwrap_or(user.dir);
     let workdir_path = CString::new(
-        user.dir
+        workdir
             .to_str()
             .expect("workdir_path should not contain invalid UTF-8"),
     )
@@ -330,6 +375,12 @@ fn main() -> Result<()> {
         "KRUN_SERVER_PORT".to_owned(),
         options.server_port.to_string(),
     );
+    if options.fex_writable {
+        env.insert("KRUN_FEX_WRITABLE".to_owned(), "1".to_owned());
+    }
+    if let Some(wayland_display) = wayland_display {
+        env.insert("WAYLAND_DISPLAY".to_owned(), wayland_display);
+    }
 
     let mut krun_config = KrunConfig {
         args: Vec::new(),
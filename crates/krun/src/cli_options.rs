@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use bpaf::{construct, long, positional, OptionParser, Parser};
+use serde::Deserialize;
+
+use crate::types::MiB;
+
+// Fully resolved settings for a krun invocation: CLI flags merged with any
+// `--config` machine config file, CLI taking precedence.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command: PathBuf,
+    pub command_args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub server_port: u16,
+    pub mem: Option<MiB>,
+    pub cpu_list: Vec<Vec<usize>>,
+    pub fex_images: Vec<String>,
+    pub passt_socket: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub workdir: Option<PathBuf>,
+    pub vsock_forwards: Vec<(u32, PathBuf)>,
+    pub fex_writable: bool,
+    pub listen: Option<SocketAddr>,
+    pub token: Option<String>,
+    pub gpu_virgl: bool,
+}
+
+// The `[vm]` table of a machine config file.
+#[derive(Debug, Default, Deserialize)]
+struct VmSection {
+    cpus: Option<Vec<Vec<usize>>>,
+    mem: Option<u32>,
+}
+
+// One entry of the `[[disk]]` array.
+#[derive(Debug, Deserialize)]
+struct DiskSection {
+    path: String,
+}
+
+// The `[fex]` table.
+#[derive(Debug, Default, Deserialize)]
+struct FexSection {
+    writable: Option<bool>,
+}
+
+// The `[gpu]` table.
+#[derive(Debug, Default, Deserialize)]
+struct GpuSection {
+    virgl: Option<bool>,
+}
+
+// The `[net]` table.
+#[derive(Debug, Default, Deserialize)]
+struct NetSection {
+    passt_socket: Option<PathBuf>,
+}
+
+// One entry of the `[[vsock]]` array, forwarding a host UNIX socket into the
+// guest on `port`.
+#[derive(Debug, Deserialize)]
+struct VsockSection {
+    port: u32,
+    path: PathBuf,
+}
+
+// The on-disk representation of a `--config machine.toml` file, merged into
+// `Options` after CLI flags have been parsed so that CLI flags win.
+#[derive(Debug, Default, Deserialize)]
+struct MachineConfig {
+    #[serde(default)]
+    vm: VmSection,
+    #[serde(default)]
+    disk: Vec<DiskSection>,
+    #[serde(default)]
+    fex: FexSection,
+    #[serde(default)]
+    gpu: GpuSection,
+    #[serde(default)]
+    net: NetSection,
+    #[serde(default)]
+    vsock: Vec<VsockSection>,
+    workdir: Option<PathBuf>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+fn parse_env_pair(raw: &str) -> Result<(String, String)> {
+    let (key, value) = raw
+        .split_once('=')
+        .with_context(|| format!("`--env {raw}` is missing a `=`, expected `KEY=VALUE`"))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn parse_cpu_group(raw: &str) -> Result<Vec<usize>> {
+    raw.split(',')
+        .map(|cpu| {
+            cpu.trim()
+                .parse::<usize>()
+                .with_context(|| format!("`{cpu}` in `--cpu-list` is not a CPU number"))
+        })
+        .collect()
+}
+
+// Shared by `--vsock` and config-file `[[vsock]]` entries: fail fast on a
+// typo'd host path instead of waiting for `krun_add_vsock_port` to reject it.
+fn check_vsock_forward_path(path: &Path) -> Result<()> {
+    if !path.exists() {
+        anyhow::bail!("`{}` does not exist", path.display());
+    }
+    Ok(())
+}
+
+fn parse_vsock_forward(raw: &str) -> Result<(u32, PathBuf)> {
+    let (port, path) = raw
+        .split_once(':')
+        .with_context(|| format!("`--vsock {raw}` is missing a `:`, expected `PORT:HOST_SOCKET_PATH`"))?;
+    let port = port
+        .parse::<u32>()
+        .with_context(|| format!("`{port}` in `--vsock` is not a valid vsock port"))?;
+    let path = PathBuf::from(path);
+    check_vsock_forward_path(&path).with_context(|| format!("`--vsock {raw}`"))?;
+    Ok((port, path))
+}
+
+// Not an `Options` field: applied to `options.token` after parsing so
+// `--token-file` and `--token` share one slot.
+fn token_file_parser() -> impl Parser<Option<PathBuf>> {
+    long("token-file")
+        .help("Read the `--listen` bearer token from PATH instead of passing it on the command line, where other local users could read it via `ps`/`/proc`")
+        .argument::<PathBuf>("PATH")
+        .optional()
+}
+
+fn options_parser() -> impl Parser<Options> {
+    let config = long("config")
+        .help("Load vCPU/RAM/disk/GPU/net settings from a TOML machine config file")
+        .argument::<PathBuf>("PATH")
+        .optional();
+    let mem = long("mem")
+        .help("Amount of RAM to give the guest, in MiB")
+        .argument::<u32>("MIB")
+        .map(MiB::from)
+        .optional();
+    let cpu_list = long("cpu-list")
+        .help("Comma-separated CPU ids to bind the microVM to, one vCPU per CPU id, repeatable")
+        .argument::<String>("CPUS")
+        .parse(|raw| parse_cpu_group(&raw))
+        .many();
+    let fex_images = long("fex-image")
+        .help("Path to a read-only FEX rootfs/overlay image, repeatable")
+        .argument::<String>("PATH")
+        .many();
+    let passt_socket = long("passt-socket")
+        .help("Connect to an already-running `passt` on this UNIX socket instead of spawning one")
+        .argument::<PathBuf>("PATH")
+        .optional();
+    let server_port = long("server-port")
+        .help("TCP port used for single-instance hand-off, also forwarded into the guest by a spawned `passt`")
+        .argument::<u16>("PORT")
+        .fallback(3334);
+    let env = long("env")
+        .help("Extra KEY=VALUE environment variable to set in the guest, repeatable")
+        .argument::<String>("KEY=VALUE")
+        .parse(|raw| parse_env_pair(&raw))
+        .many();
+    let fex_writable = long("fex-writable")
+        .help("Give the guest a writable upper layer on top of the FEX rootfs overlay")
+        .switch();
+    let vsock_forwards = long("vsock")
+        .help("Forward a host UNIX socket into the guest as `PORT:HOST_SOCKET_PATH`, repeatable")
+        .argument::<String>("PORT:HOST_SOCKET_PATH")
+        .parse(|raw| parse_vsock_forward(&raw))
+        .many();
+    let listen = long("listen")
+        .help("Accept launch requests on ADDR:PORT from other processes/hosts, not just locally")
+        .argument::<SocketAddr>("ADDR:PORT")
+        .optional();
+    let token = long("token")
+        .help("Bearer token required from `--listen` clients before a command is accepted; prefer `--token-file` since this is visible to other local users via `ps`")
+        .argument::<String>("TOKEN")
+        .optional();
+    let command = positional::<PathBuf>("COMMAND");
+    let command_args = positional::<String>("ARGS").many();
+    // Not exposed as a flag of its own: only ever populated from `--config`,
+    // once the parsed `Options` is available.
+    let workdir = bpaf::pure(None);
+    // Likewise only ever overridden by `--config`'s `[gpu] virgl = false`.
+    let gpu_virgl = bpaf::pure(true);
+
+    construct!(Options {
+        config,
+        mem,
+        cpu_list,
+        fex_images,
+        passt_socket,
+        server_port,
+        env,
+        command,
+        command_args,
+        workdir,
+        vsock_forwards,
+        fex_writable,
+        listen,
+        token,
+        gpu_virgl,
+    })
+}
+
+// Load a machine config file and apply it to `options`, filling in only the
+// fields the user did not already pass on the command line.
+fn apply_machine_config(options: &mut Options, path: &PathBuf) -> Result<()> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read machine config `{}`", path.display()))?;
+    let config: MachineConfig = toml::from_str(&raw)
+        .with_context(|| format!("Failed to parse machine config `{}`", path.display()))?;
+
+    if options.mem.is_none() {
+        options.mem = config.vm.mem.map(MiB::from);
+    }
+    if options.cpu_list.is_empty() {
+        if let Some(cpus) = config.vm.cpus {
+            options.cpu_list = cpus;
+        }
+    }
+    if options.fex_images.is_empty() && !config.disk.is_empty() {
+        options.fex_images = config.disk.into_iter().map(|disk| disk.path).collect();
+    }
+    if options.passt_socket.is_none() {
+        options.passt_socket = config.net.passt_socket;
+    }
+    if options.workdir.is_none() {
+        options.workdir = config.workdir;
+    }
+    if options.vsock_forwards.is_empty() && !config.vsock.is_empty() {
+        options.vsock_forwards = config
+            .vsock
+            .into_iter()
+            .map(|forward| {
+                check_vsock_forward_path(&forward.path).with_context(|| {
+                    format!("`[[vsock]]` entry for port {}", forward.port)
+                })?;
+                Ok((forward.port, forward.path))
+            })
+            .collect::<Result<_>>()?;
+    }
+    if options.env.is_empty() && !config.env.is_empty() {
+        options.env = config.env.into_iter().collect();
+    }
+    if !options.fex_writable {
+        options.fex_writable = config.fex.writable.unwrap_or(false);
+    }
+
+    if let Some(virgl) = config.gpu.virgl {
+        options.gpu_virgl = virgl;
+    }
+
+    Ok(())
+}
+
+pub fn options() -> OptionParser<Options> {
+    construct!(options_parser(), token_file_parser())
+        .map(|(mut options, token_file)| {
+            if let Some(token_file) = token_file {
+                let token = fs::read_to_string(&token_file)
+                    .with_context(|| format!("Failed to read `--token-file {}`", token_file.display()))
+                    .map(|contents| contents.trim().to_string());
+                match token {
+                    Ok(token) => options.token = Some(token),
+                    Err(err) => {
+                        eprintln!("{err:#}");
+                        std::process::exit(1);
+                    },
+                }
+            }
+            if let Some(config_path) = options.config.clone() {
+                if let Err(err) = apply_machine_config(&mut options, &config_path) {
+                    eprintln!("Failed to apply `--config {}`: {err:#}", config_path.display());
+                    std::process::exit(1);
+                }
+            }
+            if options.listen.is_some() && options.token.is_none() {
+                eprintln!("`--listen` requires `--token` or `--token-file` to authenticate incoming requests");
+                std::process::exit(1);
+            }
+            options
+        })
+        .to_options()
+        .descr("Run a command inside a libkrun microVM")
+}
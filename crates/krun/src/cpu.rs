@@ -0,0 +1,20 @@
+use anyhow::{Context, Result};
+use rustix::process::{sched_getaffinity, CpuSet, Pid};
+
+// Returns every CPU in this process's affinity mask, each as its own
+// single-CPU group. Performance and efficiency cores are not told apart, and
+// sibling threads are not grouped together.
+pub fn get_performance_cores() -> Result<Vec<Vec<usize>>> {
+    let cpuset = sched_getaffinity(Pid::from_raw(0)).context("Failed to read CPU affinity")?;
+    let cores: Vec<Vec<usize>> = (0..CpuSet::MAX_CPU)
+        .filter(|&cpu| cpuset.is_set(cpu))
+        .map(|cpu| vec![cpu])
+        .collect();
+    Ok(cores)
+}
+
+// Used when `get_performance_cores` fails. It returns the same affinity-mask
+// CPUs, so it fails whenever `get_performance_cores` does.
+pub fn get_fallback_cores() -> Result<Vec<Vec<usize>>> {
+    get_performance_cores()
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+// Locate a krun helper binary (`krun-guest`, `krun-server`, ...) installed
+// next to this executable, falling back to `$PATH`.
+pub fn find_krun_exec(name: &str) -> Result<String> {
+    let self_exe = env::current_exe().ok();
+    if let Some(dir) = self_exe.as_deref().and_then(Path::parent) {
+        let candidate = dir.join(name);
+        if candidate.exists() {
+            return Ok(candidate
+                .to_str()
+                .ok_or_else(|| anyhow!("`{name}` path contains invalid UTF-8"))?
+                .to_string());
+        }
+    }
+
+    for path in env::split_paths(&env::var("PATH").unwrap_or_default()) {
+        let candidate = path.join(name);
+        if candidate.exists() {
+            return Ok(candidate
+                .to_str()
+                .ok_or_else(|| anyhow!("`{name}` path contains invalid UTF-8"))?
+                .to_string());
+        }
+    }
+
+    Err(anyhow!("Could not find `{name}` in the executable directory or `$PATH`"))
+}
+
+// Folds the `--env KEY=VALUE` pairs collected on the CLI into a lookup map,
+// later overlaid with whatever the guest process itself needs (e.g.
+// `KRUN_SERVER_PORT`).
+pub fn prepare_env_vars(env: Vec<(String, String)>) -> Result<HashMap<String, String>> {
+    Ok(env.into_iter().collect())
+}
@@ -1,6 +1,6 @@
 use std::ffi::CString;
 use std::fs::{read_dir, File};
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::os::fd::AsFd;
 use std::path::Path;
 
@@ -30,9 +30,81 @@ fn mkdir_fex(dir: &str) {
     .unwrap();
 }
 
+// Requested by the host via `--fex-writable` (or the matching config field)
+// and passed down as an env var.
+fn fex_writable_requested() -> bool {
+    std::env::var("KRUN_FEX_WRITABLE").is_ok_and(|value| value == "1")
+}
+
+// Creates the tmpfs upper layer an overlay needs to be writable and returns
+// the `upperdir=...,workdir=...` overlay options fragment.
+//
+// This must live outside `/run/fex-emu/`: that directory is the parent of
+// the per-image lower mounts (`/run/fex-emu/vda`, ...), and mounting a
+// tmpfs directly on top of it would shadow those already-mounted lowers.
+fn make_fex_upper() -> Result<String> {
+    let dir = "/run/fex-emu-rw/";
+    let upper = dir.to_string() + "upper";
+    let work = dir.to_string() + "work";
+
+    mkdir_fex(dir);
+    make_tmpfs(dir)?;
+    mkdir_fex(&upper);
+    mkdir_fex(&work);
+
+    Ok(format!("upperdir={upper},workdir={work}"))
+}
+
+const SQUASHFS_MAGIC: [u8; 4] = 0x73717368_u32.to_le_bytes();
+const EROFS_MAGIC: [u8; 4] = 0xE0F5E1E2_u32.to_le_bytes();
+
+// Sniff the superblock magic to tell squashfs and erofs images apart, since
+// both are distributed in the wild.
+fn detect_image_fs(path: &str) -> Option<&'static str> {
+    let mut file = File::open(path).ok()?;
+
+    let mut squashfs_magic = [0u8; 4];
+    file.read_exact(&mut squashfs_magic).ok()?;
+    if squashfs_magic == SQUASHFS_MAGIC {
+        return Some("squashfs");
+    }
+
+    let mut erofs_magic = [0u8; 4];
+    file.seek(SeekFrom::Start(1024)).ok()?;
+    file.read_exact(&mut erofs_magic).ok()?;
+    if erofs_magic == EROFS_MAGIC {
+        return Some("erofs");
+    }
+
+    None
+}
+
+// Mounts a FEX rootfs image at `dir` as the filesystem type detected from its
+// superblock. Only when the magic is not recognised are both supported types
+// tried, erofs first and then squashfs.
+fn mount_fex_image(path: &str, dir: &str, flags: MountFlags) -> Result<()> {
+    let fs_types = match detect_image_fs(path) {
+        Some(fs_type) => vec![fs_type],
+        None => vec!["erofs", "squashfs"],
+    };
+
+    let mut last_err = None;
+    for fs_type in fs_types {
+        match mount2(Some(path), dir, Some(fs_type), flags, None) {
+            Ok(()) => return Ok(()),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.expect("at least one filesystem type is always tried")).context(format!(
+        "Failed to mount `{path}` as squashfs or erofs"
+    ))
+}
+
 fn mount_fex_rootfs() -> Result<()> {
     let dir = "/run/fex-emu/";
     let dir_rootfs = dir.to_string() + "rootfs";
+    let writable = fex_writable_requested();
 
     // Make base directories
     mkdir_fex(dir);
@@ -51,26 +123,34 @@ fn mount_fex_rootfs() -> Result<()> {
         let path = file.path().into_os_string().into_string().unwrap();
         let dir = dir.to_string() + &name;
 
-        // Mount the erofs images.
+        // Mount the squashfs/erofs images.
         mkdir_fex(&dir);
-        mount2(Some(path), dir.clone(), Some("erofs"), flags, None)
-            .context("Failed to mount erofs")
-            .unwrap();
+        mount_fex_image(&path, &dir, flags).unwrap();
         images.push(dir);
     }
 
-    if images.len() >= 2 {
+    // A single lower can be exposed with a plain symlink only when it also
+    // stays read-only; overlaying a single lower with an upper still
+    // requires a real overlay mount.
+    if images.len() >= 2 || (images.len() == 1 && writable) {
         // Overlay the mounts together.
-        let opts = format!(
+        let lowerdir = format!(
             "lowerdir={}",
             images.into_iter().rev().collect::<Vec<String>>().join(":")
         );
+        let (opts, mount_flags) = if writable {
+            let upper = make_fex_upper()?;
+            (format!("{lowerdir},{upper}"), MountFlags::empty())
+        } else {
+            (lowerdir, flags)
+        };
         let opts = CString::new(opts).unwrap();
         let overlay = "overlay".to_string();
         let overlay_ = Some(&overlay);
 
         mkdir_fex(&dir_rootfs);
-        mount2(overlay_, &dir_rootfs, overlay_, flags, Some(&opts)).context("Failed to overlay")?;
+        mount2(overlay_, &dir_rootfs, overlay_, mount_flags, Some(&opts))
+            .context("Failed to overlay")?;
     } else if images.len() == 1 {
         // Just expose the one mount
         symlink(&images[0], &dir_rootfs)?;
@@ -144,5 +224,19 @@ pub fn mount_filesystems() -> Result<()> {
         make_tmpfs("/tmp/.X11-unix")?;
     }
 
+    // `WAYLAND_DISPLAY` is only present here when the host actually forwarded
+    // a Wayland socket (see the `krun.rs` vsock block it's set alongside);
+    // on an X11-only host it stays unset so the guest falls back to X11
+    // instead of pointing at a socket nothing will ever create.
+    if let Ok(wayland_display) = std::env::var("WAYLAND_DISPLAY") {
+        if let Ok(run_dir) = std::env::var("XDG_RUNTIME_DIR") {
+            let socket_path = Path::new(&run_dir).join(&wayland_display);
+            if let Some(parent) = socket_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create `{}`", parent.display()))?;
+            }
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,218 @@
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rustix::fs::{flock, FlockOperation};
+use serde::{Deserialize, Serialize};
+
+// The outcome of trying to become (or reach) the single krun instance for
+// this server port.
+pub enum LaunchResult {
+    // Another krun instance already holds the lock and ran `command` on our
+    // behalf; this is the exit code it reported back.
+    LaunchRequested { exit_code: i32 },
+    // We are the first instance: we hold the lock and should boot the
+    // microVM ourselves.
+    LockAcquired {
+        lock_file: File,
+        command: PathBuf,
+        command_args: Vec<String>,
+        env: Vec<(String, String)>,
+    },
+}
+
+// Keeps the pre-auth allocation tiny regardless of what a client sends.
+const MAX_TOKEN_LEN: usize = 1024;
+// `--listen` is reachable from other hosts, so this must be small enough
+// that an unauthenticated or malicious peer can't force a huge allocation.
+const MAX_PAYLOAD_LEN: usize = 1024 * 1024;
+// Same reasoning: a slow or silent peer must not be able to park a thread
+// forever waiting on it.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Sent only after the bearer token (if any) has already been read and
+// verified.
+#[derive(Serialize, Deserialize)]
+struct LaunchPayload {
+    command: PathBuf,
+    command_args: Vec<String>,
+    env: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LaunchResponse {
+    exit_code: i32,
+}
+
+fn read_bytes(stream: &mut TcpStream, max_len: usize) -> Result<Vec<u8>> {
+    let mut len_buf = [0u8; 4];
+    stream
+        .read_exact(&mut len_buf)
+        .context("Failed to read frame length")?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > max_len {
+        anyhow::bail!("Frame length {len} exceeds the {max_len} byte limit");
+    }
+    let mut buf = vec![0u8; len];
+    stream
+        .read_exact(&mut buf)
+        .context("Failed to read frame body")?;
+    Ok(buf)
+}
+
+fn write_bytes(stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
+    stream
+        .write_all(&(payload.len() as u32).to_be_bytes())
+        .context("Failed to write frame length")?;
+    stream
+        .write_all(payload)
+        .context("Failed to write frame body")
+}
+
+fn read_json_frame<T: serde::de::DeserializeOwned>(
+    stream: &mut TcpStream,
+    max_len: usize,
+) -> Result<T> {
+    let buf = read_bytes(stream, max_len)?;
+    serde_json::from_slice(&buf).context("Failed to parse frame")
+}
+
+fn write_json_frame<T: Serialize>(stream: &mut TcpStream, value: &T) -> Result<()> {
+    let payload = serde_json::to_vec(value).context("Failed to serialize frame")?;
+    write_bytes(stream, &payload)
+}
+
+// Constant-time comparison so a remote `--listen` client can't learn the
+// token byte-by-byte from response timing.
+fn tokens_match(expected: &str, actual: &str) -> bool {
+    let expected = expected.as_bytes();
+    let actual = actual.as_bytes();
+    if expected.len() != actual.len() {
+        return false;
+    }
+    expected
+        .iter()
+        .zip(actual)
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+fn lock_path(server_port: u16) -> Result<PathBuf> {
+    let run_dir = env::var("XDG_RUNTIME_DIR").context("`XDG_RUNTIME_DIR` is not set")?;
+    Ok(Path::new(&run_dir).join(format!("krun-{server_port}.lock")))
+}
+
+// Try to become the single krun instance bound to `server_port`. If one is
+// already running, hand it `command`/`command_args`/`env` to launch instead
+// of starting a second microVM.
+pub fn launch_or_lock(
+    server_port: u16,
+    command: PathBuf,
+    command_args: Vec<String>,
+    env: Vec<(String, String)>,
+) -> Result<LaunchResult> {
+    let lock_file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path(server_port)?)
+        .context("Failed to open krun lock file")?;
+
+    if flock(&lock_file, FlockOperation::NonBlockingLockExclusive).is_ok() {
+        return Ok(LaunchResult::LockAcquired {
+            lock_file,
+            command,
+            command_args,
+            env,
+        });
+    }
+
+    let payload = LaunchPayload {
+        command,
+        command_args,
+        env,
+    };
+    let mut stream = TcpStream::connect(("127.0.0.1", server_port))
+        .context("Failed to connect to the running krun instance")?;
+    // No token is required on the local loopback hand-off; send an empty one
+    // to keep the wire format identical to the `--listen` path.
+    write_bytes(&mut stream, b"").context("Failed to send launch token")?;
+    write_json_frame(&mut stream, &payload).context("Failed to send launch request")?;
+    let response: LaunchResponse =
+        read_json_frame(&mut stream, MAX_PAYLOAD_LEN).context("Failed to read launch response")?;
+
+    Ok(LaunchResult::LaunchRequested {
+        exit_code: response.exit_code,
+    })
+}
+
+// Runs one accepted launch request to completion and writes its exit status
+// back to the caller. The bearer token is read and checked before the
+// (larger, attacker-influenced) command payload is ever allocated.
+fn handle_connection(mut stream: TcpStream, token: Option<String>) {
+    let Ok(provided_token) = read_bytes(&mut stream, MAX_TOKEN_LEN) else {
+        return;
+    };
+
+    if let Some(expected) = &token {
+        let provided = String::from_utf8_lossy(&provided_token);
+        if !tokens_match(expected, &provided) {
+            let _ = write_json_frame(&mut stream, &LaunchResponse { exit_code: -1 });
+            return;
+        }
+    }
+
+    let payload: LaunchPayload = match read_json_frame(&mut stream, MAX_PAYLOAD_LEN) {
+        Ok(payload) => payload,
+        Err(_) => return,
+    };
+
+    let status = Command::new(&payload.command)
+        .args(&payload.command_args)
+        .envs(payload.env.iter().cloned())
+        .status();
+    let exit_code = status.ok().and_then(|status| status.code()).unwrap_or(-1);
+    let _ = write_json_frame(&mut stream, &LaunchResponse { exit_code });
+}
+
+fn serve(listener: TcpListener, token: Option<String>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        if stream.set_read_timeout(Some(CONNECTION_TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(CONNECTION_TIMEOUT)).is_err()
+        {
+            continue;
+        }
+        let token = token.clone();
+        thread::spawn(move || handle_connection(stream, token));
+    }
+}
+
+// Starts serving the launch protocol in the background: always locally on
+// `127.0.0.1:server_port` for the existing single-instance hand-off, and
+// additionally on `listen_addr` when `--listen` was given, in which case
+// `token` is required on every request accepted there.
+pub fn spawn_listeners(
+    server_port: u16,
+    listen_addr: Option<SocketAddr>,
+    token: Option<String>,
+) -> Result<()> {
+    let local_listener = TcpListener::bind(("127.0.0.1", server_port))
+        .context("Failed to bind the local launch listener")?;
+    thread::spawn(move || serve(local_listener, None));
+
+    if let Some(listen_addr) = listen_addr {
+        let remote_listener =
+            TcpListener::bind(listen_addr).context("Failed to bind `--listen` address")?;
+        thread::spawn(move || serve(remote_listener, token));
+    }
+
+    Ok(())
+}
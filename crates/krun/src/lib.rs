@@ -0,0 +1,7 @@
+pub mod cli_options;
+pub mod cpu;
+pub mod env;
+pub mod guest;
+pub mod launch;
+pub mod net;
+pub mod types;
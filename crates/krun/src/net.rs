@@ -0,0 +1,34 @@
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+// Connect to a `passt` instance already listening on a UNIX socket, e.g. one
+// managed by the container runtime.
+pub fn connect_to_passt(passt_socket: impl AsRef<Path>) -> Result<UnixStream> {
+    UnixStream::connect(passt_socket.as_ref()).context("Failed to connect to `passt` socket")
+}
+
+// Spawn a `passt` instance dedicated to this microVM and hand back the
+// guest-facing end of the socketpair it was started with.
+pub fn start_passt(server_port: u16) -> Result<UnixStream> {
+    let (guest_side, passt_side) =
+        UnixStream::pair().context("Failed to create socketpair for `passt`")?;
+    // Both ends are created close-on-exec; clear that on passt's end so the
+    // child inherits it under the number passed to `--fd`.
+    rustix::io::fcntl_setfd(&passt_side, rustix::io::FdFlags::empty())
+        .context("Failed to clear close-on-exec on the `passt` socket")?;
+
+    Command::new("passt")
+        .arg("--fd")
+        .arg(rustix::fd::AsRawFd::as_raw_fd(&passt_side).to_string())
+        .arg("-t")
+        .arg(server_port.to_string())
+        .stdin(Stdio::null())
+        .spawn()
+        .context("Failed to spawn `passt`")?;
+
+    drop(passt_side);
+    Ok(guest_side)
+}
@@ -0,0 +1,16 @@
+// Distinct from a bare `u32` so MiB and byte counts can't get mixed up at
+// the `krun_set_vm_config` boundary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MiB(u32);
+
+impl From<u32> for MiB {
+    fn from(value: u32) -> Self {
+        MiB(value)
+    }
+}
+
+impl From<MiB> for u32 {
+    fn from(value: MiB) -> Self {
+        value.0
+    }
+}